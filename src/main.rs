@@ -2,10 +2,14 @@ use clap::{Args, Parser, Subcommand};
 use colored::*;
 
 use std::env;
+use std::sync::Arc;
 
+mod pattern;
 mod sync;
+mod transport;
+mod watch;
 
-use sync::{MatchingResult, Sync};
+use sync::{MatchingResult, Sync, SyncOptions};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -13,6 +17,21 @@ use sync::{MatchingResult, Sync};
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// When a sync has a genuine conflict (two members share the newest
+    /// timestamp but differ in content), pick this path or host:path as
+    /// the winning source instead of skipping the sync
+    #[arg(long)]
+    force: Option<String>,
+
+    /// Show what a sync would do without overwriting any files (remote
+    /// copies are still fetched to compare them)
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Also report files that are already up to date
+    #[arg(long)]
+    verbose: bool,
 }
 
 #[derive(Subcommand)]
@@ -22,13 +41,25 @@ enum Commands {
     /// Display information about a sync
     Show(NameArgs),
     /// Creates a new synchrnoization and name it
-    New(NameArgs),
+    New(NewArgs),
     /// Delete a synchronization, files are kept
     Del(NameArgs),
     /// Add files to an existing synchronization
     Add(NameAndFileListArgs),
     /// Remove files to an existing synchronization
     Rm(NameAndFileListArgs),
+    /// Add glob patterns to exclude when expanding a synchronization's files
+    Ignore(NameAndFileListArgs),
+    /// Keep running and automatically re-sync as tracked files change
+    Watch(WatchArgs),
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// How often, in seconds, to re-check remote endpoints (they can't be
+    /// watched for changes, so they're polled instead)
+    #[arg(long, default_value_t = 30)]
+    interval: u64,
 }
 
 #[derive(Args)]
@@ -47,6 +78,14 @@ struct NameArgs {
     name: String,
 }
 
+#[derive(Args)]
+struct NewArgs {
+    name: String,
+    /// How this sync moves files between its members
+    #[arg(long, default_value = "scp")]
+    transport: String,
+}
+
 #[derive(Args)]
 struct FolderArgs {
     folder: Option<String>,
@@ -112,12 +151,22 @@ fn main() {
                         name.bright_green()
                     );
                 }
-                None => {
-                    let new_sync = Sync::new(args.name.clone());
-                    syncs.push(new_sync);
-                    Sync::save_all(&syncs);
-                    println!("successfully created: {}", args.name.bright_green());
-                }
+                None => match sync::TransportKind::parse(&args.transport) {
+                    Some(transport) => {
+                        let mut new_sync = Sync::new(args.name.clone());
+                        new_sync.transport = transport;
+                        syncs.push(new_sync);
+                        Sync::save_all(&syncs);
+                        println!("successfully created: {}", args.name.bright_green());
+                    }
+                    None => {
+                        println!(
+                            "{} unknown transport {}, expected one of scp, rsync, local",
+                            "error:".red(),
+                            args.transport.bright_yellow()
+                        );
+                    }
+                },
             },
             Commands::Del(args) => match select_by_name(&syncs, &args.name, true) {
                 Some(name) => {
@@ -131,6 +180,7 @@ fn main() {
                 Some(name) => {
                     println!("name: {}", name.bright_green());
                     let sync = syncs.iter().find(|x| x.name == name).unwrap();
+                    println!("transport: {}", sync.transport.label().bright_blue());
                     println!("local files ({}):", sync.locals.len());
                     for file in &sync.locals {
                         println!("\t{}", file.bright_yellow());
@@ -206,6 +256,34 @@ fn main() {
                     None => {}
                 }
             }
+            Commands::Ignore(args) => {
+                let out = select_by_name(&syncs, &args.name, true).map(|name| {
+                    syncs
+                        .iter_mut()
+                        .find(|x| x.name == name)
+                        .unwrap()
+                        .add_ignores(&args.files);
+                });
+                if out.is_some() {
+                    Sync::save_all(&syncs);
+                    println!("successfully added ignores to {}:", args.name.bright_green());
+                    for pattern in &args.files {
+                        println!("\t{}", pattern.bright_yellow());
+                    }
+                }
+            }
+            Commands::Watch(args) => {
+                let path = current_dir();
+                let names: Vec<String> = select_by_folder(&syncs, &path)
+                    .iter()
+                    .map(|s| s.name.clone())
+                    .collect();
+                if names.is_empty() {
+                    println!("found no sync in {}", path.bright_blue());
+                } else {
+                    watch::run(Arc::new(syncs), names, args.interval);
+                }
+            }
             Commands::Ls(args) => {
                 let default = current_dir();
                 let path = match &args.folder {
@@ -222,7 +300,7 @@ fn main() {
                         println!("matching files:");
                         match sync.matching_files(&path) {
                             MatchingResult::Local(l) => {
-                                for ele in l {
+                                for ele in &l {
                                     println!("\t{}", ele.bright_yellow());
                                 }
                             }
@@ -239,8 +317,13 @@ fn main() {
         None =>  {
            let path = current_dir();
            let selected = select_by_folder(&syncs, &path);
+           let opts = SyncOptions {
+               force: cli.force.as_deref(),
+               dry_run: cli.dry_run,
+               verbose: cli.verbose,
+           };
            for sync in &selected {
-            sync.sync();
+            sync.sync(&opts);
            }
         },
     };
@@ -0,0 +1,283 @@
+use glob::Pattern;
+use home::home_dir;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Per-directory ignore file, checked alongside a sync's own ignore list.
+const IGNORE_FILE: &str = ".rusyncignore";
+
+/// Whether `path` should be treated as a glob pattern rather than a
+/// concrete file.
+pub(crate) fn is_glob(path: &str) -> bool {
+    path.contains('*') || path.contains('?') || path.contains('[')
+}
+
+/// Expands a glob `pattern` (e.g. `~/notes/**/*.md`) into the files that
+/// currently match it, walking the pattern's static prefix and skipping
+/// anything matched by `ignores` or a `.rusyncignore` found anywhere
+/// between the static prefix and the matched file, cascading down the
+/// tree the same way git's `.gitignore` does (a nested `.rusyncignore`
+/// adds to, rather than replaces, its ancestors' rules).
+///
+/// Returns each match as `(absolute path, path relative to the pattern's
+/// static prefix)`. The relative path is what lets a caller pair up
+/// matches from two different patterns that mirror the same tree (e.g.
+/// `~/notes/**` and `~/backup/notes/**` both matching `journal/a.md`)
+/// without confusing them with unrelated files the same pattern matched.
+pub(crate) fn expand(pattern: &str, ignores: &[String]) -> Vec<(String, String)> {
+    let pattern = expand_tilde(pattern);
+    let glob_pattern = match Pattern::new(&pattern) {
+        Ok(p) => p,
+        Err(_) => return Vec::new(),
+    };
+    let root = static_prefix(&pattern);
+    let extra: Vec<Pattern> = ignores.iter().filter_map(|p| Pattern::new(p).ok()).collect();
+
+    // Cascaded ignore patterns in effect for a given directory, i.e. its
+    // parent's cascaded patterns plus its own `.rusyncignore`. Populated
+    // as each directory is visited; a file's entry is always preceded by
+    // its parent's in `WalkDir`'s pre-order traversal.
+    let mut cascaded: HashMap<PathBuf, Vec<Pattern>> = HashMap::new();
+    let mut out = Vec::new();
+
+    for entry in WalkDir::new(&root).into_iter().filter_map(|e| e.ok()) {
+        let parent_patterns = entry
+            .path()
+            .parent()
+            .and_then(|p| cascaded.get(p))
+            .map(Vec::as_slice)
+            .unwrap_or(&extra);
+
+        if entry.file_type().is_dir() {
+            let mut patterns = parent_patterns.to_vec();
+            patterns.extend(dir_ignores(entry.path()));
+            cascaded.insert(entry.path().to_path_buf(), patterns);
+            continue;
+        }
+
+        if let Some(path) = entry.path().to_str() {
+            if glob_pattern.matches(path) && !parent_patterns.iter().any(|ig| ig.matches(path)) {
+                out.push((path.to_string(), relative_to(&root, path)));
+            }
+        }
+    }
+
+    out
+}
+
+/// `path` with `root` stripped off the front, so two patterns rooted at
+/// different directories can be compared by what's left (their position
+/// inside the tree each one walks).
+fn relative_to(root: &str, path: &str) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .trim_start_matches('/')
+        .to_string()
+}
+
+/// Expands a leading `~/` to the user's home directory, so a pattern that
+/// `anchor_to_cwd` kept as `~/...` resolves against the home directory.
+/// `Sync::add_files` does not expand `~/` in a concrete path.
+fn expand_tilde(pattern: &str) -> String {
+    match pattern.strip_prefix("~/") {
+        Some(rest) => match home_dir() {
+            Some(home) => home.join(rest).to_string_lossy().into_owned(),
+            None => pattern.to_string(),
+        },
+        None => pattern.to_string(),
+    }
+}
+
+/// Anchors a relative glob `pattern` to the current working directory at
+/// add time, the same way `Sync::add_files` canonicalizes a concrete
+/// relative path, so its expansion doesn't later depend on whatever
+/// directory `rusync` happens to run from (cron, `watch`, ...). Patterns
+/// that are already absolute, or start with `~/` (anchored to the home
+/// directory regardless of cwd), are returned unchanged.
+pub(crate) fn anchor_to_cwd(pattern: &str) -> String {
+    if pattern.starts_with("~/") || Path::new(pattern).is_absolute() {
+        return pattern.to_string();
+    }
+    match std::env::current_dir() {
+        Ok(cwd) => cwd
+            .join(pattern)
+            .to_str()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| pattern.to_string()),
+        Err(_) => pattern.to_string(),
+    }
+}
+
+/// The directory a glob `pattern`'s matches live under, and whether the
+/// pattern can match files in nested subdirectories (so a watcher needs
+/// to watch it recursively to notice them), e.g. for `watch` to know
+/// what to register with the filesystem watcher.
+pub(crate) fn watch_root(pattern: &str) -> (PathBuf, bool) {
+    let pattern = expand_tilde(pattern);
+    let prefix = static_prefix(&pattern);
+    // `*` also matches `/` in `Pattern::matches`, so any `*` past the static
+    // prefix, or a further path segment, can reach into subdirectories.
+    let rest = pattern
+        .strip_prefix(prefix.as_str())
+        .unwrap_or(&pattern)
+        .trim_start_matches('/');
+    let recursive = rest.contains('*') || rest.contains('/');
+    (PathBuf::from(prefix), recursive)
+}
+
+/// The leading path segments of `pattern` that contain no glob
+/// metacharacters, i.e. the directory the walk should start from. Falls
+/// back to the current directory for a pattern with no static prefix at
+/// all (e.g. `*.md`), since `WalkDir::new("")` finds nothing.
+fn static_prefix(pattern: &str) -> String {
+    let prefix = pattern
+        .split('/')
+        .take_while(|part| !part.contains('*') && !part.contains('?') && !part.contains('['))
+        .collect::<Vec<_>>()
+        .join("/");
+    if prefix.is_empty() {
+        ".".to_string()
+    } else {
+        prefix
+    }
+}
+
+/// The ignore patterns from `dir`'s own `.rusyncignore`, if it has one.
+/// Does not look at any other directory; callers cascade these down from
+/// parent to child themselves.
+fn dir_ignores(dir: &Path) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+    if let Ok(contents) = std::fs::read_to_string(dir.join(IGNORE_FILE)) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                if let Ok(p) = Pattern::new(line) {
+                    patterns.push(p);
+                }
+            }
+        }
+    }
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    /// A scratch directory under the OS temp dir, removed on drop.
+    struct TempDir {
+        path: PathBuf,
+    }
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!(
+                "rusync-pattern-test-{}-{}-{}",
+                std::process::id(),
+                name,
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&path).expect("failed to create test dir");
+            TempDir { path }
+        }
+
+        fn file(&self, rel: &str, contents: &str) -> &TempDir {
+            let path = self.path.join(rel);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).expect("failed to create test subdir");
+            }
+            std::fs::write(path, contents).expect("failed to write test file");
+            self
+        }
+
+        fn join(&self, rel: &str) -> String {
+            self.path.join(rel).to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn static_prefix_stops_at_first_glob_segment() {
+        assert_eq!(static_prefix("/home/user/notes/*.md"), "/home/user/notes");
+        assert_eq!(static_prefix("/home/user/notes/**"), "/home/user/notes");
+    }
+
+    #[test]
+    fn static_prefix_falls_back_to_cwd_with_no_static_segment() {
+        assert_eq!(static_prefix("*.md"), ".");
+    }
+
+    #[test]
+    fn expand_matches_files_and_reports_relative_path() {
+        let dir = TempDir::new("expand");
+        dir.file("a.md", "a").file("sub/b.md", "b").file("c.txt", "c");
+
+        let mut matches = expand(&dir.join("**/*.md"), &[]);
+        matches.sort();
+
+        assert_eq!(
+            matches,
+            vec![
+                (dir.join("a.md"), "a.md".to_string()),
+                (dir.join("sub/b.md"), "sub/b.md".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_cascades_rusyncignore_down_the_tree() {
+        let dir = TempDir::new("cascade");
+        // `.rusyncignore` patterns are matched against the full absolute
+        // path (like any other ignore pattern), so they need a wildcard
+        // to match regardless of where the tree is rooted on disk.
+        dir.file("a.md", "a")
+            .file("sub/b.md", "b")
+            .file("sub/c.md", "c")
+            .file(".rusyncignore", "*a.md")
+            .file("sub/.rusyncignore", "*c.md");
+
+        let mut matches: Vec<String> = expand(&dir.join("**/*.md"), &[])
+            .into_iter()
+            .map(|(_, rel)| rel)
+            .collect();
+        matches.sort();
+
+        // `a.md` dropped by the root ignore, `sub/c.md` by `sub`'s own
+        // ignore on top of it, `sub/b.md` kept since nothing excludes it.
+        assert_eq!(matches, vec!["sub/b.md".to_string()]);
+    }
+
+    #[test]
+    fn expand_applies_extra_ignores_from_the_sync() {
+        let dir = TempDir::new("extra-ignore");
+        dir.file("a.md", "a").file("b.md", "b");
+
+        let matches: Vec<String> = expand(&dir.join("*.md"), &[dir.join("a.md")])
+            .into_iter()
+            .map(|(_, rel)| rel)
+            .collect();
+
+        assert_eq!(matches, vec!["b.md".to_string()]);
+    }
+
+    #[test]
+    fn anchor_to_cwd_leaves_absolute_and_tilde_patterns_unchanged() {
+        assert_eq!(anchor_to_cwd("/abs/*.md"), "/abs/*.md");
+        assert_eq!(anchor_to_cwd("~/notes/*.md"), "~/notes/*.md");
+    }
+
+    #[test]
+    fn anchor_to_cwd_prefixes_relative_patterns_with_cwd() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(anchor_to_cwd("notes/*.md"), cwd.join("notes/*.md").to_str().unwrap());
+    }
+}
@@ -1,28 +1,176 @@
+use crate::pattern;
+use crate::transport::{LocalTransport, RsyncTransport, ScpTransport, Transport};
 use colored::*;
 use home::home_dir;
 use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
+/// Bumped on every [`Sync::sync`] call so two invocations racing on the
+/// same process never share a scratch fetch path.
+static SYNC_INVOCATION: AtomicU64 = AtomicU64::new(0);
+
+/// Grouping key shared by every concrete (non-glob) local and every
+/// remote, so they keep being compared together as copies of one file —
+/// only glob-expanded locals are split out by relative path. Not a valid
+/// relative path, so it can never collide with one.
+const DIRECT_KEY: &str = "\0direct";
+
 pub(crate) struct Sync {
     pub(crate) name: String,
+    /// Concrete local paths, or glob patterns (e.g. `~/notes/**/*.md`)
+    /// expanded lazily at sync time.
     pub(crate) locals: Vec<String>,
     pub(crate) remotes: Vec<(String, String)>,
+    /// Glob patterns excluded from any of the above, in addition to
+    /// whatever `.rusyncignore` files are found while expanding them.
+    pub(crate) ignores: Vec<String>,
+    pub(crate) transport: TransportKind,
+}
+
+/// Which [`Transport`] a sync uses to move files between its members.
+/// Selectable per-sync; defaults to `scp`.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TransportKind {
+    Scp,
+    Rsync,
+    Local,
+}
+
+impl Default for TransportKind {
+    fn default() -> Self {
+        TransportKind::Scp
+    }
+}
+
+impl TransportKind {
+    pub(crate) fn parse(s: &str) -> Option<TransportKind> {
+        match s {
+            "scp" => Some(TransportKind::Scp),
+            "rsync" => Some(TransportKind::Rsync),
+            "local" => Some(TransportKind::Local),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            TransportKind::Scp => "scp",
+            TransportKind::Rsync => "rsync",
+            TransportKind::Local => "local",
+        }
+    }
+
+    fn backend(&self) -> Box<dyn Transport> {
+        match self {
+            TransportKind::Scp => Box::new(ScpTransport),
+            TransportKind::Rsync => Box::new(RsyncTransport),
+            TransportKind::Local => Box::new(LocalTransport),
+        }
+    }
 }
 
 const SYNC_FILE: &str = ".rusync";
+
+/// Only used to detect and migrate the legacy `$RUSEP$`/`$FILES$` store
+/// that predates the TOML format; never written anymore.
 const SEPARATOR: &str = "$RUSEP$";
 const FILES_SEP: &str = "$FILES$";
 
+/// On-disk shape of `~/.rusync`. Kept separate from [`Sync`] so the
+/// in-memory type stays free to evolve without breaking the file format.
+#[derive(Serialize, Deserialize)]
+struct SyncFile {
+    #[serde(rename = "sync", default)]
+    sync: Vec<SyncEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncEntry {
+    name: String,
+    #[serde(default)]
+    locals: Vec<String>,
+    #[serde(default)]
+    remotes: Vec<RemoteEntry>,
+    #[serde(default)]
+    ignores: Vec<String>,
+    #[serde(default)]
+    transport: TransportKind,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteEntry {
+    host: String,
+    path: String,
+}
+
+impl From<&Sync> for SyncEntry {
+    fn from(sync: &Sync) -> Self {
+        SyncEntry {
+            name: sync.name.clone(),
+            locals: sync.locals.clone(),
+            remotes: sync
+                .remotes
+                .iter()
+                .map(|(host, path)| RemoteEntry {
+                    host: host.clone(),
+                    path: path.clone(),
+                })
+                .collect(),
+            ignores: sync.ignores.clone(),
+            transport: sync.transport,
+        }
+    }
+}
+
+impl From<SyncEntry> for Sync {
+    fn from(entry: SyncEntry) -> Self {
+        Sync {
+            name: entry.name,
+            locals: entry.locals,
+            remotes: entry
+                .remotes
+                .into_iter()
+                .map(|r| (r.host, r.path))
+                .collect(),
+            ignores: entry.ignores,
+            transport: entry.transport,
+        }
+    }
+}
+
 fn paths_match(root: &String, sub_file: &String) -> bool {
     sub_file.starts_with(root)
 }
 
+/// Turns a local entry as typed by the user into the form `Sync` actually
+/// stores: a glob is anchored to the current directory, and a concrete
+/// path is canonicalized and, if it names a directory, rewritten into a
+/// recursive glob over its contents (so it expands at sync time instead
+/// of being tracked, and failing to hash, as one opaque entry). Shared by
+/// `add_files` and `remove_files` so removing a local matches whatever
+/// form adding it actually stored.
+fn normalize_local(file: &str) -> String {
+    if pattern::is_glob(file) {
+        pattern::anchor_to_cwd(file)
+    } else {
+        let base = Path::new(file);
+        let true_path = base.canonicalize().unwrap_or(base.to_path_buf());
+        let a = true_path.to_str().unwrap();
+        if true_path.is_dir() {
+            format!("{}/**", a.trim_end_matches('/'))
+        } else {
+            a.to_owned()
+        }
+    }
+}
+
 fn remote_to_path(dist_path: &(String, String)) -> String {
     let mut out = String::new();
     out += &dist_path.0;
@@ -31,9 +179,22 @@ fn remote_to_path(dist_path: &(String, String)) -> String {
     out
 }
 
-pub(crate) enum MatchingResult<'a> {
+pub(crate) enum MatchingResult {
     Remote(Vec<String>),
-    Local(Vec<&'a String>),
+    Local(Vec<String>),
+}
+
+/// Flags that shape how [`Sync::sync`] decides and reports its work.
+#[derive(Default)]
+pub(crate) struct SyncOptions<'a> {
+    /// Picks the winning source (its path, or `host:path` for a remote)
+    /// when a conflict is detected.
+    pub(crate) force: Option<&'a str>,
+    /// Computes the plan (remote copies are still fetched to compare them)
+    /// but never pushes anything to a member.
+    pub(crate) dry_run: bool,
+    /// Also reports members that are already up to date.
+    pub(crate) verbose: bool,
 }
 
 impl Sync {
@@ -42,6 +203,8 @@ impl Sync {
             name,
             locals: Vec::new(),
             remotes: Vec::new(),
+            ignores: Vec::new(),
+            transport: TransportKind::default(),
         };
     }
 
@@ -56,28 +219,30 @@ impl Sync {
         match Self::get_path() {
             Some(path) => {
                 let display = path.display();
-                let mut file = match File::create(&path) {
-                    Err(why) => panic!("couldn't open {}: {}", display, why),
-                    Ok(file) => file,
+                let tmp_path = path.with_file_name(format!("{}.tmp.{}", SYNC_FILE, std::process::id()));
+                let file_contents = SyncFile {
+                    sync: syncs.iter().map(SyncEntry::from).collect(),
                 };
-                let mut s = String::new();
-                for sync in syncs {
-                    s += SEPARATOR;
-                    s += &sync.name;
-                    s += FILES_SEP;
-                    s += &sync.locals.join("\n");
-                    if !sync.remotes.is_empty() {
-                        s += "\n";
-                        s += &sync
-                            .remotes
-                            .iter()
-                            .map(remote_to_path)
-                            .collect::<Vec<String>>()
-                            .join("\n");
+                let s = toml::to_string_pretty(&file_contents).expect("failed to serialize syncs");
+                {
+                    let mut file = match File::create(&tmp_path) {
+                        Err(why) => panic!("couldn't open {}: {}", tmp_path.display(), why),
+                        Ok(file) => file,
+                    };
+                    match file.write_all(s.as_bytes()) {
+                        Err(e) => panic!("couldn't save sync: {}", e),
+                        Ok(_) => {}
+                    }
+                    match file.sync_all() {
+                        Err(e) => panic!("couldn't flush {}: {}", display, e),
+                        Ok(_) => {}
                     }
                 }
-                match &file.write_all(s.as_bytes()) {
-                    Err(e) => panic!("couldn't save sync: {}", e),
+                // Rename within the same directory is atomic on Unix, so a
+                // reader only ever sees the old complete file or the new
+                // one, never a partial write.
+                match std::fs::rename(&tmp_path, &path) {
+                    Err(e) => panic!("couldn't finalize {}: {}", display, e),
                     Ok(_) => {}
                 }
             }
@@ -101,22 +266,18 @@ impl Sync {
                 match file.read_to_string(&mut s) {
                     Err(why) => panic!("couldn't read {}: {}", display, why),
                     Ok(_) => {
-                        let mut out = Vec::new();
-                        for el in s.split(SEPARATOR) {
-                            match el.split_once(FILES_SEP) {
-                                Some((name, files)) => {
-                                    let mut new_sync = Sync::new(name.to_string());
-                                    if files.contains("\n") && !files.is_empty() {
-                                        new_sync.add_files(
-                                            &files.split("\n").map(|x| x.to_string()).collect(),
-                                        );
-                                    }
-                                    out.push(new_sync);
-                                }
-                                _ => {}
-                            }
+                        if s.trim().is_empty() {
+                            Vec::new()
+                        } else if s.contains(SEPARATOR) || s.contains(FILES_SEP) {
+                            let syncs = Self::load_legacy(&s);
+                            println!("migrating {} to the TOML format", display);
+                            Self::save_all(&syncs);
+                            syncs
+                        } else {
+                            let file_contents: SyncFile = toml::from_str(&s)
+                                .unwrap_or_else(|e| panic!("couldn't parse {}: {}", display, e));
+                            file_contents.sync.into_iter().map(Sync::from).collect()
                         }
-                        out
                     }
                 }
             }
@@ -124,6 +285,26 @@ impl Sync {
         }
     }
 
+    /// Parses the legacy `$RUSEP$`/`$FILES$` store, kept only so existing
+    /// `~/.rusync` files can be migrated to TOML on first load.
+    fn load_legacy(s: &str) -> Vec<Sync> {
+        let mut out = Vec::new();
+        for el in s.split(SEPARATOR) {
+            match el.split_once(FILES_SEP) {
+                Some((name, files)) => {
+                    let mut new_sync = Sync::new(name.to_string());
+                    if files.contains("\n") && !files.is_empty() {
+                        new_sync
+                            .add_files(&files.split("\n").map(|x| x.to_string()).collect());
+                    }
+                    out.push(new_sync);
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
     pub fn add_files(&mut self, files: &Vec<String>) -> Vec<bool> {
         let mut out = Vec::new();
         for file in files {
@@ -134,10 +315,7 @@ impl Sync {
                     out.push(true)
                 }
                 None => {
-                    let base = Path::new(file);
-                    let true_path = base.canonicalize().unwrap_or(base.to_path_buf());
-                    let a = true_path.to_str().unwrap();
-                    self.locals.push(a.to_owned());
+                    self.locals.push(normalize_local(file));
                     out.push(false)
                 }
             }
@@ -145,6 +323,41 @@ impl Sync {
         out
     }
 
+    /// Adds glob patterns that are always excluded when expanding this
+    /// sync's local patterns, in addition to any `.rusyncignore` found.
+    pub fn add_ignores(&mut self, patterns: &Vec<String>) {
+        self.ignores.extend(patterns.iter().cloned());
+    }
+
+    /// Expands every local entry, resolving glob patterns against the
+    /// filesystem and leaving concrete paths untouched.
+    pub(crate) fn expand_locals(&self) -> Vec<String> {
+        self.expand_locals_grouped()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Like [`Self::expand_locals`], but pairs each match with the key of
+    /// the logical file it belongs to: the files a glob pattern expanded
+    /// to are keyed by their path relative to that pattern's root, so two
+    /// patterns mirroring the same tree pair up file-by-file instead of
+    /// all being treated as copies of one file. A concrete (non-glob)
+    /// local already names one specific file, so it shares [`DIRECT_KEY`]
+    /// with every other concrete local and remote, matching the
+    /// single-group behavior `sync_with` has always used for them.
+    fn expand_locals_grouped(&self) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        for entry in &self.locals {
+            if pattern::is_glob(entry) {
+                out.extend(pattern::expand(entry, &self.ignores));
+            } else {
+                out.push((entry.clone(), DIRECT_KEY.to_string()));
+            }
+        }
+        out
+    }
+
     pub fn remove_files(&mut self, files: &Vec<String>) -> Vec<(bool, bool)> {
         let mut out = Vec::new();
         for file in files {
@@ -159,8 +372,14 @@ impl Sync {
                     }
                 }
                 None => {
-                    if self.locals.contains(file) {
-                        self.locals.retain(|y| y != file);
+                    // `add_files` never stores a local exactly as typed
+                    // (it anchors globs to cwd and rewrites directories
+                    // into a `/**` glob), so a raw-string match here would
+                    // never find what `add` just added; normalize the
+                    // same way before comparing.
+                    let normalized = normalize_local(file);
+                    if self.locals.contains(&normalized) {
+                        self.locals.retain(|y| y != &normalized);
                         out.push((true, false));
                     } else {
                         out.push((false, false));
@@ -181,7 +400,7 @@ impl Sync {
                 host == &qhost.to_string() && paths_match(&qpath.to_string(), path)
             }),
             None => self
-                .locals
+                .expand_locals()
                 .iter()
                 .any(|file| paths_match(&path.to_string(), file)),
         }
@@ -199,94 +418,530 @@ impl Sync {
                     .collect(),
             ),
             None => MatchingResult::Local(
-                self.locals
-                    .iter()
+                self.expand_locals()
+                    .into_iter()
                     .filter(|file| paths_match(&path.to_string(), file))
                     .collect(),
             ),
         }
     }
 
-    pub fn sync(&self) {
+    /// Syncs every member to the newest one. `force` picks the winning
+    /// member (its path, or `host:path` for a remote) when two members
+    /// share the newest timestamp but differ in content.
+    pub fn sync(&self, opts: &SyncOptions) {
+        let backend = self.transport.backend();
+        // Unique per *invocation*, not just per `Sync`: `watch`'s poll
+        // thread and its debounce loop can both call `sync()` on the same
+        // `Sync` back to back, and a scratch path shared across those two
+        // calls would let one invocation hash/stat the fetch the other is
+        // still writing.
+        let invocation = SYNC_INVOCATION.fetch_add(1, Ordering::Relaxed);
+        let dst = env::temp_dir().join(format!(
+            "file.{}.{}.{}",
+            self.name,
+            std::process::id(),
+            invocation
+        ));
+        self.sync_with(backend.as_ref(), &dst, opts);
+    }
+
+    /// Does the actual work of [`Sync::sync`] against an injected
+    /// [`Transport`] and scratch path, so the mtime/digest decision logic
+    /// (conflict detection, `--force`, skip-when-identical) can be
+    /// exercised in tests without spawning `scp`.
+    fn sync_with(&self, backend: &dyn Transport, dst: &Path, opts: &SyncOptions) {
+        let force = opts.force;
         let bar = ProgressBar::new_spinner();
         bar.enable_steady_tick(Duration::from_millis(100));
-        let dir = env::temp_dir();
-        let dst = dir.join("file");
-
-        let mut latest = 0;
-
-        let mut targets = Vec::new();
-        let mut others = Vec::new();
-
-        for path in &self.locals {
-            bar.set_message(format!("{} to update. checking {}", targets.len(), path));
-            let mtime = get_mtime(&dst);
-            if mtime > latest {
-                latest = mtime;
-                targets.append(&mut others);
-                others.push(path);
-            } else if mtime == latest {
-                others.push(path);
-            } else {
-                targets.push(path);
+
+        let mut candidates: Vec<Candidate> = Vec::new();
+
+        for (path, group) in self.expand_locals_grouped() {
+            bar.set_message(format!("checking {}", path));
+            let local_path = PathBuf::from(&path);
+            if let Some(digest) = hash_file(&local_path) {
+                let mtime = backend.stat(&local_path).map(|m| m.mtime).unwrap_or(0);
+                candidates.push(Candidate {
+                    id: path,
+                    group,
+                    mtime,
+                    digest,
+                });
             }
         }
-        let remote_paths: Vec<String> = self.remotes.iter().map(remote_to_path).collect();
 
-        for path in &remote_paths {
-            bar.set_message(format!("{} to update. checking {}", targets.len(), path));
+        for path in self.remotes.iter().map(remote_to_path) {
+            bar.set_message(format!("checking {}", path));
+            if backend.fetch(&path, dst) {
+                if let Some(digest) = hash_file(dst) {
+                    let mtime = backend.stat(dst).map(|m| m.mtime).unwrap_or(0);
+                    candidates.push(Candidate {
+                        id: path,
+                        group: DIRECT_KEY.to_string(),
+                        mtime,
+                        digest,
+                    });
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            bar.finish_and_clear();
+            return;
+        }
+
+        // A glob can expand to several unrelated files (e.g. `notes/*.md`
+        // matching both `a.md` and `b.md`): each is its own logical file
+        // and must go through its own digest/mtime comparison, not be
+        // folded into one winner-take-all group with the rest of the
+        // sync. `group` is `DIRECT_KEY` for every member that already
+        // named one specific file (a concrete local, or a remote), so
+        // those still land in a single group together, as before.
+        let mut groups: Vec<(String, Vec<Candidate>)> = Vec::new();
+        for candidate in candidates {
+            match groups.iter_mut().find(|(key, _)| *key == candidate.group) {
+                Some((_, members)) => members.push(candidate),
+                None => groups.push((candidate.group.clone(), vec![candidate])),
+            }
+        }
+
+        // Remotes and concrete locals don't expand, so they never carry a
+        // glob's relative-path key: fold the `DIRECT_KEY` group into the
+        // one glob group it unambiguously corresponds to (the common case
+        // of a directory mirrored to a single remote counterpart), so
+        // they're still hashed/mtime-compared against it instead of
+        // silently sitting out of the sync. If the glob matched more than
+        // one file there's no way to tell which one the remote/static
+        // local pairs with, so leave it on its own and say so instead of
+        // quietly dropping it from the sync.
+        if let Some(direct_idx) = groups.iter().position(|(key, _)| key == DIRECT_KEY) {
+            let other_groups = groups.len() - 1;
+            if other_groups == 1 {
+                let (_, direct_members) = groups.remove(direct_idx);
+                groups[0].1.extend(direct_members);
+            } else if other_groups > 1 {
+                bar.println(format!(
+                    "{} {} mixes a remote or plain local with a glob matching {} different files; it won't be compared against any of them — point it at a single file to sync it",
+                    "warning:".yellow(),
+                    self.name.bright_green(),
+                    other_groups
+                ));
+            }
+        }
 
-            match scp(path.to_string(), dst.to_str().expect("msg").to_string()) {
-                Some(0) => {
-                    let mtime = get_mtime(&dst);
-                    if mtime > latest {
-                        latest = mtime;
-                        targets.append(&mut others);
-                        others.push(&path);
-                    } else if mtime == latest {
-                        others.push(&path);
+        let mut updated = 0usize;
+        let mut had_conflict = false;
+        // Whether any group actually needed syncing, `dry_run` or not:
+        // `updated` alone misses a dry run, which plans a sync but never
+        // touches it, and would otherwise fall through to the "already up
+        // to date" message right below the plan it just printed.
+        let mut had_sync = false;
+        for (_, members) in &groups {
+            match resolve_group(members, force) {
+                GroupOutcome::UpToDate => {}
+                GroupOutcome::Conflict(newest) => {
+                    had_conflict = true;
+                    bar.println(format!(
+                        "{} {} has conflicting versions with the same timestamp, pass {} to pick a winner:",
+                        "conflict:".red(),
+                        self.name.bright_green(),
+                        "--force".bright_yellow()
+                    ));
+                    for c in &newest {
+                        bar.println(format!("\t{}", c.id.yellow()));
+                    }
+                }
+                GroupOutcome::Sync { source, targets } => {
+                    had_sync = true;
+                    if opts.verbose {
+                        for up_to_date in members
+                            .iter()
+                            .filter(|c| c.digest == source.digest && c.id != source.id)
+                        {
+                            bar.println(format!(
+                                "\t{} already up to date",
+                                format_id(&up_to_date.id)
+                            ));
+                        }
+                    }
+
+                    if opts.dry_run {
+                        bar.println(format!(
+                            "{} would sync from {} (mtime {}):",
+                            self.name.bright_green(),
+                            format_id(&source.id),
+                            source.mtime
+                        ));
+                        for target in &targets {
+                            bar.println(format!("\t{}", format_id(&target.id)));
+                        }
                     } else {
-                        targets.push(&path);
+                        for target in &targets {
+                            bar.set_message(format!("updating {} ", target.id));
+                            backend.push(&source.id, &target.id);
+                        }
+                        updated += targets.len();
                     }
                 }
-                _ => {}
             }
         }
-        let source = others.first().unwrap();
-        for target in &targets {
-            bar.set_message(format!("updating {} ", target));
-            scp(source.to_string(), target.to_string());
-        }
+
         bar.finish_and_clear();
-        if !targets.is_empty() {
+        if updated > 0 {
             println!(
                 "{} updated {} file{}",
                 self.name.bright_green(),
-                targets.len(),
-                if targets.len() == 1 { "" } else { "s" }
+                updated,
+                if updated == 1 { "" } else { "s" }
             );
+        } else if !had_conflict && !had_sync && opts.verbose {
+            println!("{} already up to date", self.name.bright_green());
         }
     }
 }
 
-fn get_mtime(path: &PathBuf) -> i64 {
-    match File::open(path) {
-        Ok(f) => match File::metadata(&f) {
-            Ok(x) => x.mtime(),
-            _ => 0,
-        },
-        _ => 0,
+/// The resolution of one logical file's [`Candidate`]s: every member
+/// already identical, a genuine conflict needing `--force`, or a source
+/// to copy over the rest.
+enum GroupOutcome<'a> {
+    UpToDate,
+    Conflict(Vec<&'a Candidate>),
+    Sync {
+        source: &'a Candidate,
+        targets: Vec<&'a Candidate>,
+    },
+}
+
+/// Picks the member every other one should be synced from, the same way
+/// `sync_with` always has: newest mtime wins, ties with identical content
+/// are fine, and a genuine tie goes to `--force` or is reported back as a
+/// conflict.
+fn resolve_group<'a>(members: &'a [Candidate], force: Option<&str>) -> GroupOutcome<'a> {
+    let reference = members[0].digest;
+    if members.iter().all(|c| c.digest == reference) {
+        return GroupOutcome::UpToDate;
+    }
+
+    let latest = members.iter().map(|c| c.mtime).max().unwrap();
+    let newest: Vec<&Candidate> = members.iter().filter(|c| c.mtime == latest).collect();
+    let source = match newest.as_slice() {
+        [one] => *one,
+        _ => {
+            let distinct = newest
+                .iter()
+                .map(|c| c.digest)
+                .collect::<std::collections::HashSet<_>>();
+            if distinct.len() == 1 {
+                newest[0]
+            } else if let Some(chosen) = force.and_then(|f| newest.iter().find(|c| c.id == f)) {
+                chosen
+            } else {
+                return GroupOutcome::Conflict(newest);
+            }
+        }
+    };
+
+    let targets: Vec<&Candidate> = members.iter().filter(|c| c.digest != source.digest).collect();
+    GroupOutcome::Sync { source, targets }
+}
+
+/// Colors `id` blue if it's a `host:path` remote, yellow if it's a local
+/// path, matching how the rest of the CLI distinguishes the two.
+fn format_id(id: &str) -> ColoredString {
+    if id.contains(':') {
+        id.bright_blue()
+    } else {
+        id.bright_yellow()
     }
 }
 
-fn scp(src: String, dst: String) -> Option<i32> {
-    Command::new("scp")
-        .arg("-p")
-        .arg(src)
-        .arg(dst)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .expect("failed to run scp")
-        .code()
+/// One member of a sync, as seen during the content-hash comparison.
+struct Candidate {
+    id: String,
+    /// The logical file this candidate is a copy of; see
+    /// [`Sync::expand_locals_grouped`].
+    group: String,
+    mtime: i64,
+    digest: blake3::Hash,
+}
+
+fn hash_file(path: &Path) -> Option<blake3::Hash> {
+    let mut file = File::open(path).ok()?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).ok()?;
+    Some(blake3::hash(&buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// In-memory [`Transport`] double. Locals are hashed straight off the
+    /// test's temp files on disk, but their reported `mtime` comes from
+    /// this map instead of the filesystem (no real stat is made) so
+    /// tests don't have to fight OS mtime granularity. Remotes are served
+    /// from `remote_contents`/`mtimes` and "fetched" into whatever scratch
+    /// path `Sync::sync_with` passes in.
+    struct FakeTransport {
+        remote_contents: HashMap<String, Vec<u8>>,
+        mtimes: HashMap<String, i64>,
+        last_fetched: RefCell<Option<String>>,
+        pushes: RefCell<Vec<(String, String)>>,
+    }
+
+    impl FakeTransport {
+        fn new() -> Self {
+            FakeTransport {
+                remote_contents: HashMap::new(),
+                mtimes: HashMap::new(),
+                last_fetched: RefCell::new(None),
+                pushes: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn with_mtime(mut self, id: &str, mtime: i64) -> Self {
+            self.mtimes.insert(id.to_string(), mtime);
+            self
+        }
+
+        fn with_remote(mut self, remote: &str, contents: &[u8], mtime: i64) -> Self {
+            self.remote_contents
+                .insert(remote.to_string(), contents.to_vec());
+            self.mtimes.insert(remote.to_string(), mtime);
+            self
+        }
+    }
+
+    impl Transport for FakeTransport {
+        fn fetch(&self, remote: &str, local_tmp: &Path) -> bool {
+            match self.remote_contents.get(remote) {
+                Some(bytes) if std::fs::write(local_tmp, bytes).is_ok() => {
+                    *self.last_fetched.borrow_mut() = Some(remote.to_string());
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        fn push(&self, local: &str, remote: &str) -> bool {
+            self.pushes
+                .borrow_mut()
+                .push((local.to_string(), remote.to_string()));
+            true
+        }
+
+        fn stat(&self, path: &Path) -> Option<crate::transport::Metadata> {
+            let key = path.to_str()?;
+            if let Some(mtime) = self.mtimes.get(key) {
+                return Some(crate::transport::Metadata { mtime: *mtime });
+            }
+            // `path` is the shared scratch file, stat'd right after a
+            // remote was fetched into it: report that remote's mtime.
+            self.last_fetched
+                .borrow()
+                .as_ref()
+                .and_then(|id| self.mtimes.get(id))
+                .map(|mtime| crate::transport::Metadata { mtime: *mtime })
+        }
+    }
+
+    struct TempFile {
+        path: PathBuf,
+    }
+
+    impl TempFile {
+        fn with_contents(name: &str, contents: &[u8]) -> TempFile {
+            let path = env::temp_dir().join(format!("rusync-test-{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).expect("failed to write test fixture");
+            TempFile { path }
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn test_sync(locals: &[&TempFile]) -> Sync {
+        let mut sync = Sync::new("test".to_string());
+        sync.locals = locals
+            .iter()
+            .map(|f| f.path.to_str().unwrap().to_string())
+            .collect();
+        sync
+    }
+
+    #[test]
+    fn sync_with_skips_when_all_identical() {
+        let a = TempFile::with_contents("identical-a", b"same content");
+        let b = TempFile::with_contents("identical-b", b"same content");
+        let sync = test_sync(&[&a, &b]);
+        let backend = FakeTransport::new()
+            .with_mtime(a.path.to_str().unwrap(), 100)
+            .with_mtime(b.path.to_str().unwrap(), 200);
+        let dst = env::temp_dir().join("rusync-test-skip-dst");
+
+        sync.sync_with(&backend, &dst, &SyncOptions::default());
+
+        assert!(backend.pushes.borrow().is_empty());
+    }
+
+    #[test]
+    fn sync_with_conflict_without_force_does_not_push() {
+        let a = TempFile::with_contents("conflict-a", b"version a");
+        let b = TempFile::with_contents("conflict-b", b"version b");
+        let sync = test_sync(&[&a, &b]);
+        // Same mtime, different content: a genuine conflict.
+        let backend = FakeTransport::new()
+            .with_mtime(a.path.to_str().unwrap(), 100)
+            .with_mtime(b.path.to_str().unwrap(), 100);
+        let dst = env::temp_dir().join("rusync-test-conflict-dst");
+
+        sync.sync_with(&backend, &dst, &SyncOptions::default());
+
+        assert!(backend.pushes.borrow().is_empty());
+    }
+
+    #[test]
+    fn sync_with_force_pushes_chosen_source() {
+        let a = TempFile::with_contents("force-a", b"version a");
+        let b = TempFile::with_contents("force-b", b"version b");
+        let sync = test_sync(&[&a, &b]);
+        let backend = FakeTransport::new()
+            .with_mtime(a.path.to_str().unwrap(), 100)
+            .with_mtime(b.path.to_str().unwrap(), 100);
+        let dst = env::temp_dir().join("rusync-test-force-dst");
+        let a_id = a.path.to_str().unwrap().to_string();
+        let b_id = b.path.to_str().unwrap().to_string();
+
+        let opts = SyncOptions {
+            force: Some(&a_id),
+            ..SyncOptions::default()
+        };
+        sync.sync_with(&backend, &dst, &opts);
+
+        assert_eq!(backend.pushes.borrow().as_slice(), [(a_id, b_id)]);
+    }
+
+    /// A scratch directory holding two distinct `.md` files, removed on
+    /// drop, used to exercise a glob local that expands to several files.
+    struct TempGlobDir {
+        dir: PathBuf,
+        a: PathBuf,
+        b: PathBuf,
+    }
+
+    impl TempGlobDir {
+        fn new() -> TempGlobDir {
+            let dir = env::temp_dir().join(format!("rusync-test-glob-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).expect("failed to create test dir");
+            let a = dir.join("a.md");
+            let b = dir.join("b.md");
+            std::fs::write(&a, b"content A").expect("failed to write test fixture");
+            std::fs::write(&b, b"content B").expect("failed to write test fixture");
+            TempGlobDir { dir, a, b }
+        }
+
+        fn glob(&self) -> String {
+            self.dir.join("*.md").to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TempGlobDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn sync_with_glob_treats_each_expanded_file_independently() {
+        let fixture = TempGlobDir::new();
+        let mut sync = Sync::new("test".to_string());
+        sync.locals = vec![fixture.glob()];
+
+        let a_id = fixture.a.to_str().unwrap().to_string();
+        let b_id = fixture.b.to_str().unwrap().to_string();
+        // `b.md` is newer than `a.md`, but the two are unrelated files
+        // that both happened to match the same glob: that must never
+        // make `b.md`'s content overwrite `a.md`.
+        let backend = FakeTransport::new().with_mtime(&a_id, 100).with_mtime(&b_id, 200);
+        let dst = env::temp_dir().join("rusync-test-glob-dst");
+
+        sync.sync_with(&backend, &dst, &SyncOptions::default());
+
+        assert!(backend.pushes.borrow().is_empty());
+        assert_eq!(std::fs::read(&fixture.a).unwrap(), b"content A");
+        assert_eq!(std::fs::read(&fixture.b).unwrap(), b"content B");
+    }
+
+    #[test]
+    fn sync_with_fetches_remote_and_pushes_to_stale_local() {
+        let local = TempFile::with_contents("remote-local", b"stale");
+        let mut sync = test_sync(&[&local]);
+        sync.remotes.push(("host".to_string(), "/remote/file".to_string()));
+        let local_id = local.path.to_str().unwrap().to_string();
+        let remote_id = "host:/remote/file".to_string();
+
+        let backend = FakeTransport::new()
+            .with_mtime(&local_id, 100)
+            .with_remote(&remote_id, b"fresh", 200);
+        let dst = env::temp_dir().join("rusync-test-remote-dst");
+
+        sync.sync_with(&backend, &dst, &SyncOptions::default());
+
+        assert_eq!(
+            backend.pushes.borrow().as_slice(),
+            [(remote_id, local_id)]
+        );
+    }
+
+    #[test]
+    fn sync_with_pairs_a_remote_against_a_glob_with_a_single_match() {
+        // A directory mirrored to one remote file is the headline use
+        // case of glob locals: the glob matching exactly one file must
+        // still be hash/mtime-compared against the remote, not silently
+        // skipped just because it came from a glob.
+        let dir = env::temp_dir().join(format!("rusync-test-glob-remote-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("failed to create test dir");
+        let local = dir.join("proj.conf");
+        std::fs::write(&local, b"stale").expect("failed to write test fixture");
+
+        let mut sync = Sync::new("test".to_string());
+        sync.locals = vec![dir.join("*.conf").to_str().unwrap().to_string()];
+        sync.remotes.push(("host".to_string(), "/remote/proj.conf".to_string()));
+        let local_id = local.to_str().unwrap().to_string();
+        let remote_id = "host:/remote/proj.conf".to_string();
+
+        let backend = FakeTransport::new()
+            .with_mtime(&local_id, 100)
+            .with_remote(&remote_id, b"fresh", 999_999_999);
+        let dst = env::temp_dir().join("rusync-test-glob-remote-dst");
+
+        sync.sync_with(&backend, &dst, &SyncOptions::default());
+
+        assert_eq!(backend.pushes.borrow().as_slice(), [(remote_id, local_id)]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn add_then_remove_a_directory_round_trips() {
+        let dir = env::temp_dir().join(format!("rusync-test-rm-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("failed to create test dir");
+        let mut sync = Sync::new("test".to_string());
+
+        sync.add_files(&vec![dir.to_str().unwrap().to_string()]);
+        assert_eq!(sync.locals.len(), 1);
+
+        let result = sync.remove_files(&vec![dir.to_str().unwrap().to_string()]);
+
+        assert_eq!(result, vec![(true, false)]);
+        assert!(sync.locals.is_empty());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
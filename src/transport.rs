@@ -0,0 +1,92 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Minimal file metadata a [`Transport`] can report back.
+pub(crate) struct Metadata {
+    pub(crate) mtime: i64,
+}
+
+/// Moves file content between a sync member and a local temp file.
+/// Abstracting this out of [`crate::sync::Sync::sync`] means the
+/// mtime/digest decision logic can be exercised against an in-memory fake
+/// instead of always spawning an external process.
+pub(crate) trait Transport {
+    /// Copies `remote` down to `local_tmp`, returning whether it succeeded.
+    fn fetch(&self, remote: &str, local_tmp: &Path) -> bool;
+    /// Copies `local` up to `remote`, returning whether it succeeded.
+    fn push(&self, local: &str, remote: &str) -> bool;
+    /// Reads metadata of a file already on the local filesystem.
+    fn stat(&self, path: &Path) -> Option<Metadata>;
+}
+
+fn run(cmd: &str, args: &[&str]) -> bool {
+    Command::new(cmd)
+        .args(args)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+pub(crate) fn stat_path(path: &Path) -> Option<Metadata> {
+    std::fs::metadata(path)
+        .ok()
+        .map(|m| Metadata { mtime: m.mtime() })
+}
+
+/// Transfers files over `scp`. The default backend: works with any host
+/// reachable over SSH, but re-sends the whole file on every change.
+pub(crate) struct ScpTransport;
+
+impl Transport for ScpTransport {
+    fn fetch(&self, remote: &str, local_tmp: &Path) -> bool {
+        run("scp", &["-p", remote, local_tmp.to_str().expect("msg")])
+    }
+
+    fn push(&self, local: &str, remote: &str) -> bool {
+        run("scp", &["-p", local, remote])
+    }
+
+    fn stat(&self, path: &Path) -> Option<Metadata> {
+        stat_path(path)
+    }
+}
+
+/// Transfers files over `rsync`, which only sends the delta between the
+/// source and destination. Cheaper than [`ScpTransport`] for large files
+/// that only change slightly between syncs.
+pub(crate) struct RsyncTransport;
+
+impl Transport for RsyncTransport {
+    fn fetch(&self, remote: &str, local_tmp: &Path) -> bool {
+        run("rsync", &["-az", remote, local_tmp.to_str().expect("msg")])
+    }
+
+    fn push(&self, local: &str, remote: &str) -> bool {
+        run("rsync", &["-az", local, remote])
+    }
+
+    fn stat(&self, path: &Path) -> Option<Metadata> {
+        stat_path(path)
+    }
+}
+
+/// Copies files on the local filesystem only, for syncs that never leave
+/// the machine (e.g. mirroring a folder onto a second disk).
+pub(crate) struct LocalTransport;
+
+impl Transport for LocalTransport {
+    fn fetch(&self, remote: &str, local_tmp: &Path) -> bool {
+        std::fs::copy(remote, local_tmp).is_ok()
+    }
+
+    fn push(&self, local: &str, remote: &str) -> bool {
+        std::fs::copy(local, remote).is_ok()
+    }
+
+    fn stat(&self, path: &Path) -> Option<Metadata> {
+        stat_path(path)
+    }
+}
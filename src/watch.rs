@@ -0,0 +1,111 @@
+use crate::pattern;
+use crate::sync::{Sync, SyncOptions};
+use colored::*;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait after the last filesystem event before re-syncing, so
+/// that a burst of saves from an editor only triggers one sync.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watches every local file tracked by `names` and re-syncs as soon as a
+/// burst of changes settles down. Remote endpoints can't be watched, so
+/// they are polled instead, re-syncing every `interval` seconds.
+pub fn run(syncs: Arc<Vec<Sync>>, names: Vec<String>, interval: u64) {
+    let selected: Vec<&Sync> = syncs.iter().filter(|s| names.contains(&s.name)).collect();
+
+    // What to watch, and whether it needs to be recursive: concrete files
+    // are watched directly, but a glob pattern is watched at its static
+    // prefix directory (recursively when it can match files in nested
+    // subdirectories, see `pattern::watch_root`) so files created
+    // after `watch` starts are noticed too, not just ones that already
+    // existed. Deduped per path since several syncs can share a prefix.
+    let mut watch_targets: HashMap<PathBuf, bool> = HashMap::new();
+    for sync in &selected {
+        for local in &sync.locals {
+            if pattern::is_glob(local) {
+                let (root, recursive) = pattern::watch_root(local);
+                let entry = watch_targets.entry(root).or_insert(false);
+                *entry = *entry || recursive;
+            } else {
+                watch_targets.entry(PathBuf::from(local)).or_insert(false);
+            }
+        }
+    }
+
+    let (tx, rx) = channel::<notify::Result<Event>>();
+    let mut watcher: RecommendedWatcher =
+        notify::recommended_watcher(tx).expect("failed to create file watcher");
+    for (path, recursive) in &watch_targets {
+        let mode = if *recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        if let Err(e) = watcher.watch(path, mode) {
+            println!(
+                "{} failed to watch {}: {}",
+                "warning:".yellow(),
+                path.display(),
+                e
+            );
+        }
+    }
+
+    {
+        let syncs = Arc::clone(&syncs);
+        let names = names.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(interval));
+            for sync in syncs
+                .iter()
+                .filter(|s| names.contains(&s.name) && !s.remotes.is_empty())
+            {
+                sync.sync(&SyncOptions::default());
+            }
+        });
+    }
+
+    println!(
+        "watching {} sync{} for changes... (remotes polled every {}s)",
+        selected.len().to_string().bright_green(),
+        if selected.len() == 1 { "" } else { "s" },
+        interval
+    );
+
+    let mut pending: HashSet<String> = HashSet::new();
+    loop {
+        match rx.recv_timeout(DEBOUNCE) {
+            Ok(Ok(event)) => {
+                for path in event.paths {
+                    if let Some(p) = path.to_str() {
+                        pending.insert(p.to_string());
+                    }
+                }
+            }
+            Ok(Err(e)) => println!("{} watch error: {}", "warning:".yellow(), e),
+            Err(_) => {
+                if pending.is_empty() {
+                    continue;
+                }
+                let mut to_sync: HashSet<&str> = HashSet::new();
+                for changed in &pending {
+                    for sync in &selected {
+                        if sync.has_file_inside(changed) {
+                            to_sync.insert(&sync.name);
+                        }
+                    }
+                }
+                for sync in selected.iter().filter(|s| to_sync.contains(s.name.as_str())) {
+                    sync.sync(&SyncOptions::default());
+                }
+                pending.clear();
+            }
+        }
+    }
+}